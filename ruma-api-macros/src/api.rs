@@ -12,7 +12,7 @@ pub(crate) mod metadata;
 pub(crate) mod request;
 pub(crate) mod response;
 
-use self::{metadata::Metadata, request::Request, response::Response};
+use self::{attribute::BodyFormat, metadata::Metadata, request::Request, response::Response};
 use crate::util;
 
 /// Removes `serde` attributes from struct fields.
@@ -50,7 +50,10 @@ impl Parse for Api {
         };
 
         let newtype_body_field = request.newtype_body_field();
-        if metadata.method == "GET" && (request.has_body_fields() || newtype_body_field.is_some()) {
+        let raw_body_field = request.raw_body_field();
+        if metadata.method == "GET"
+            && (request.has_body_fields() || newtype_body_field.is_some() || raw_body_field.is_some())
+        {
             let mut combined_error: Option<syn::Error> = None;
             let mut add_error = |field| {
                 let error = syn::Error::new_spanned(field, "GET endpoints can't have body fields");
@@ -69,9 +72,60 @@ impl Parse for Api {
                 add_error(field);
             }
 
+            if let Some(field) = raw_body_field {
+                add_error(field);
+            }
+
             return Err(combined_error.unwrap());
         }
 
+        if request.has_conflicting_body_fields() {
+            return Err(syn::Error::new_spanned(
+                request.raw_body_field().unwrap(),
+                "`#[ruma_api(raw_body)]` can't be combined with other body fields",
+            ));
+        }
+
+        if response.has_conflicting_body_fields() {
+            return Err(syn::Error::new_spanned(
+                response.raw_body_field().unwrap(),
+                "`#[ruma_api(raw_body)]` can't be combined with other body fields",
+            ));
+        }
+
+        if let Some(field) = request.newtype_body_field() {
+            if request.body_format() != BodyFormat::Json {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    "`#[ruma_api(body)]` only supports the default (JSON) `body_format`: a \
+                     single typed body field can't be split across multipart parts or form \
+                     fields",
+                ));
+            }
+        }
+
+        if let Some(field) = response.newtype_body_field() {
+            if response.body_format() != BodyFormat::Json {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    "`#[ruma_api(body)]` only supports the default (JSON) `body_format`: a \
+                     single typed body field can't be split across multipart parts or form \
+                     fields",
+                ));
+            }
+        }
+
+        if metadata.authentication == "QueryOnlyAccessToken" {
+            if let Some(field) = request.query_map_field() {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    "`#[ruma_api(query_map)]` can't be combined with the `QueryOnlyAccessToken` \
+                     authentication scheme: a caller-controlled `access_token` entry in the map \
+                     would collide with the real one",
+                ));
+            }
+        }
+
         Ok(Self { metadata, request, response, error_ty })
     }
 }
@@ -89,6 +143,7 @@ pub fn expand_all(api: Api) -> syn::Result<TokenStream> {
     let path = &api.metadata.path;
     let rate_limited = &api.metadata.rate_limited;
     let authentication = &api.metadata.authentication;
+    let timeout = api.metadata.timeout_tokens();
 
     let request_type = &api.request;
     let response_type = &api.response;
@@ -108,7 +163,11 @@ pub fn expand_all(api: Api) -> syn::Result<TokenStream> {
     let (request_path_string, parse_request_path) =
         util::request_path_string_and_parse(&api.request, &api.metadata, &ruma_api_import);
 
-    let request_query_string = util::build_query_string(&api.request, &ruma_api_import);
+    let request_query_string = util::build_query_string(
+        &api.request,
+        &ruma_api_import,
+        authentication == "QueryOnlyAccessToken",
+    );
 
     let extract_request_query = util::extract_request_query(&api.request, &ruma_api_import);
 
@@ -123,6 +182,27 @@ pub fn expand_all(api: Api) -> syn::Result<TokenStream> {
     };
 
     let mut header_kvs = api.request.append_header_kvs();
+    let has_request_body = api.request.has_body_fields()
+        || api.request.newtype_body_field().is_some()
+        || api.request.raw_body_field().is_some();
+    // A bare `raw_body` field carries its own `Content-Type`. In multipart mode it is only a file
+    // part inside the multipart envelope, which gets the format's content type like any other.
+    let raw_body_is_bare = api.request.raw_body_field().is_some()
+        && api.request.body_format() != BodyFormat::Multipart;
+    if has_request_body && !raw_body_is_bare {
+        let content_type = match api.request.body_format() {
+            BodyFormat::Json => "application/json",
+            BodyFormat::FormUrlEncoded => "application/x-www-form-urlencoded",
+            BodyFormat::Multipart => "multipart/form-data",
+        };
+
+        header_kvs.extend(quote! {
+            req_builder = req_builder.header(
+                #ruma_api_import::exports::http::header::CONTENT_TYPE,
+                #content_type,
+            );
+        });
+    }
     if authentication == "AccessToken" {
         header_kvs.extend(quote! {
             req_builder = req_builder.header(
@@ -147,38 +227,75 @@ pub fn expand_all(api: Api) -> syn::Result<TokenStream> {
         TokenStream::new()
     };
 
-    let extract_request_body =
-        if api.request.has_body_fields() || api.request.newtype_body_field().is_some() {
-            let body_lifetimes = if api.request.has_body_lifetimes() {
-                // duplicate the anonymous lifetime as many times as needed
-                let lifetimes =
-                    std::iter::repeat(quote! { '_ }).take(api.request.body_lifetime_count());
-                quote! { < #( #lifetimes, )* >}
-            } else {
-                TokenStream::new()
+    let json_request_body_decl = || {
+        let body_lifetimes = if api.request.has_body_lifetimes() {
+            // duplicate the anonymous lifetime as many times as needed
+            let lifetimes =
+                std::iter::repeat(quote! { '_ }).take(api.request.body_lifetime_count());
+            quote! { < #( #lifetimes, )* >}
+        } else {
+            TokenStream::new()
+        };
+        quote! {
+            let request_body: <
+                RequestBody #body_lifetimes
+                as #ruma_api_import::exports::ruma_serde::Outgoing
+            >::Incoming = {
+                // If the request body is completely empty, pretend it is an empty JSON object
+                // instead. This allows requests with only optional body parameters to be
+                // deserialized in that case.
+                let json = match request.body().as_slice() {
+                    b"" => b"{}",
+                    body => body,
+                };
+
+                #ruma_api_import::try_deserialize!(
+                    request,
+                    #ruma_api_import::exports::serde_json::from_slice(json)
+                )
             };
+        }
+    };
+
+    let extract_request_body = if let Some(raw_field) = api.request.raw_body_field() {
+        if api.request.body_format() == BodyFormat::Multipart {
+            // In multipart mode, a `raw_body` field is sent as a file part rather than the bare
+            // body, whether or not it's accompanied by other (text-part) fields — so it has to
+            // be extracted out of the multipart envelope here too. One parse of the body yields
+            // both the file part and the remaining fields, deserialized into `RequestBody` as in
+            // the plain multipart case.
+            let file_part_name =
+                raw_field.ident.as_ref().expect("body fields have a name").to_string();
             quote! {
-                let request_body: <
-                    RequestBody #body_lifetimes
-                    as #ruma_api_import::exports::ruma_serde::Outgoing
-                >::Incoming = {
-                    // If the request body is completely empty, pretend it is an empty JSON object
-                    // instead. This allows requests with only optional body parameters to be
-                    // deserialized in that case.
-                    let json = match request.body().as_slice() {
-                        b"" => b"{}",
-                        body => body,
-                    };
-
-                    #ruma_api_import::try_deserialize!(
-                        request,
-                        #ruma_api_import::exports::serde_json::from_slice(json)
-                    )
-                };
+                let (request_raw_body, request_body): (Vec<u8>, RequestBody) =
+                    #ruma_api_import::exports::multipart::parse_form_with_file(
+                        request.body(),
+                        #file_part_name,
+                    )?;
             }
         } else {
-            TokenStream::new()
-        };
+            quote! {
+                // A `raw_body` field takes the request body verbatim, bypassing `serde_json`.
+                let request_body = request.body().clone();
+            }
+        }
+    } else if api.request.newtype_body_field().is_some() {
+        json_request_body_decl()
+    } else if api.request.has_body_fields() {
+        match api.request.body_format() {
+            BodyFormat::Json => json_request_body_decl(),
+            BodyFormat::FormUrlEncoded => quote! {
+                let request_body: RequestBody =
+                    #ruma_api_import::exports::serde_html_form::from_bytes(request.body())?;
+            },
+            BodyFormat::Multipart => quote! {
+                let request_body: RequestBody =
+                    #ruma_api_import::exports::multipart::parse_form(request.body())?;
+            },
+        }
+    } else {
+        TokenStream::new()
+    };
 
     let parse_request_headers = if api.request.has_header_fields() {
         api.request.parse_headers_from_request()
@@ -198,36 +315,119 @@ pub fn expand_all(api: Api) -> syn::Result<TokenStream> {
         TokenStream::new()
     };
 
-    let typed_response_body_decl =
-        if api.response.has_body_fields() || api.response.newtype_body_field().is_some() {
-            quote! {
-                let response_body: <
-                    ResponseBody
-                    as #ruma_api_import::exports::ruma_serde::Outgoing
-                >::Incoming = {
-                    // If the reponse body is completely empty, pretend it is an empty JSON object
-                    // instead. This allows reponses with only optional body parameters to be
-                    // deserialized in that case.
-                    let json = match response.body().as_slice() {
-                        b"" => b"{}",
-                        body => body,
-                    };
-
-                    #ruma_api_import::try_deserialize!(
-                        response,
-                        #ruma_api_import::exports::serde_json::from_slice(json),
-                    )
+    let json_response_body_decl = || {
+        quote! {
+            let response_body: <
+                ResponseBody
+                as #ruma_api_import::exports::ruma_serde::Outgoing
+            >::Incoming = {
+                // If the reponse body is completely empty, pretend it is an empty JSON object
+                // instead. This allows reponses with only optional body parameters to be
+                // deserialized in that case.
+                let json = match response.body().as_slice() {
+                    b"" => b"{}",
+                    body => body,
                 };
+
+                #ruma_api_import::try_deserialize!(
+                    response,
+                    #ruma_api_import::exports::serde_json::from_slice(json),
+                )
+            };
+        }
+    };
+
+    let typed_response_body_decl = if let Some(raw_field) = api.response.raw_body_field() {
+        if api.response.body_format() == BodyFormat::Multipart {
+            // See the analogous branch in `extract_request_body` above: a `raw_body` field is
+            // sent as a file part in multipart mode regardless of whether other fields are also
+            // present, so it has to be extracted out of the multipart envelope here too.
+            let file_part_name =
+                raw_field.ident.as_ref().expect("body fields have a name").to_string();
+            quote! {
+                let (response_raw_body, response_body): (Vec<u8>, ResponseBody) =
+                    #ruma_api_import::exports::multipart::parse_form_with_file(
+                        response.body(),
+                        #file_part_name,
+                    )?;
             }
         } else {
-            TokenStream::new()
-        };
+            quote! {
+                // A `raw_body` field takes the response body verbatim, bypassing `serde_json`.
+                let response_body = response.body().clone();
+            }
+        }
+    } else if api.response.newtype_body_field().is_some() {
+        json_response_body_decl()
+    } else if api.response.has_body_fields() {
+        match api.response.body_format() {
+            BodyFormat::Json => json_response_body_decl(),
+            BodyFormat::FormUrlEncoded => quote! {
+                let response_body: ResponseBody =
+                    #ruma_api_import::exports::serde_html_form::from_bytes(response.body())?;
+            },
+            BodyFormat::Multipart => quote! {
+                let response_body: ResponseBody =
+                    #ruma_api_import::exports::multipart::parse_form(response.body())?;
+            },
+        }
+    } else {
+        TokenStream::new()
+    };
 
     let response_init_fields = api.response.init_fields();
 
     let serialize_response_headers = api.response.apply_header_fields();
 
-    let body = api.response.to_body();
+    let body = api.response.to_body(&ruma_api_import);
+
+    // A bare `raw_body` field carries its own `Content-Type` (normally via a `#[ruma_api(header =
+    // CONTENT_TYPE)]` field), so don't force one on it like we do for every other response. In
+    // multipart mode the raw field is only a file part, and the envelope gets the usual header.
+    let default_content_type_header = if api.response.raw_body_field().is_some()
+        && api.response.body_format() != BodyFormat::Multipart
+    {
+        TokenStream::new()
+    } else {
+        let content_type = match api.response.body_format() {
+            BodyFormat::Json => "application/json",
+            BodyFormat::FormUrlEncoded => "application/x-www-form-urlencoded",
+            BodyFormat::Multipart => "multipart/form-data",
+        };
+
+        quote! {
+            .header(
+                #ruma_api_import::exports::http::header::CONTENT_TYPE,
+                #content_type,
+            )
+        }
+    };
+
+    let extract_access_token = if authentication == "AccessToken" {
+        quote! {
+            request
+                .headers()
+                .get(#ruma_api_import::exports::http::header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .map(::std::string::ToString::to_string)
+        }
+    } else if authentication == "QueryOnlyAccessToken" {
+        quote! {
+            request.uri().query().and_then(|query| {
+                query.split('&').find_map(|pair| {
+                    let (key, value) = pair.split_once('=')?;
+                    (key == "access_token").then(|| {
+                        #ruma_api_import::exports::percent_encoding::percent_decode_str(value)
+                            .decode_utf8_lossy()
+                            .into_owned()
+                    })
+                })
+            })
+        }
+    } else {
+        quote! { ::std::option::Option::None }
+    };
 
     let metadata_doc = format!("Metadata for the `{}` API endpoint.", name);
     let request_doc =
@@ -288,10 +488,7 @@ pub fn expand_all(api: Api) -> syn::Result<TokenStream> {
             #[allow(unused_variables)]
             fn try_from(response: Response) -> ::std::result::Result<Self, Self::Error> {
                 let mut resp_builder = #ruma_api_import::exports::http::Response::builder()
-                    .header(
-                        #ruma_api_import::exports::http::header::CONTENT_TYPE,
-                        "application/json",
-                    );
+                    #default_content_type_header;
 
                 let mut headers = resp_builder
                     .headers_mut()
@@ -342,6 +539,7 @@ pub fn expand_all(api: Api) -> syn::Result<TokenStream> {
             path: #path,
             rate_limited: #rate_limited,
             authentication: #ruma_api_import::AuthScheme::#authentication,
+            timeout: #timeout,
         };
 
         impl #request_lifetimes #ruma_api_import::OutgoingRequest
@@ -395,6 +593,26 @@ pub fn expand_all(api: Api) -> syn::Result<TokenStream> {
             const METADATA: #ruma_api_import::Metadata = self::METADATA;
         }
 
+        impl #incoming_request_type {
+            /// Turns a raw incoming HTTP request into this endpoint's request type, additionally
+            /// extracting the access token according to this endpoint's authentication scheme.
+            ///
+            /// This centralizes the per-endpoint `Authorization` header (or, depending on the
+            /// authentication scheme, query string) parsing that every server implementation
+            /// would otherwise have to duplicate by hand.
+            #[allow(unused_variables)]
+            pub fn from_http_request_with_auth(
+                request: #ruma_api_import::exports::http::Request<Vec<u8>>,
+            ) -> ::std::result::Result<
+                (Self, ::std::option::Option<::std::string::String>),
+                #ruma_api_import::error::FromHttpRequestError,
+            > {
+                let access_token = #extract_access_token;
+                let request = <Self as ::std::convert::TryFrom<_>>::try_from(request)?;
+                Ok((request, access_token))
+            }
+        }
+
         #non_auth_endpoint_impls
     })
 }
@@ -417,3 +635,113 @@ impl Parse for ErrorType {
         Ok(Self { error_kw, ty })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use proc_macro2::TokenStream;
+    use quote::quote;
+
+    use super::Api;
+
+    fn base_metadata() -> TokenStream {
+        quote! {
+            metadata: {
+                description: "test",
+                method: POST,
+                name: "test_endpoint",
+                path: "/test",
+                rate_limited: false,
+                authentication: None,
+            }
+        }
+    }
+
+    #[test]
+    fn raw_body_conflicts_with_other_fields_by_default() {
+        let metadata = base_metadata();
+        let tokens = quote! {
+            #metadata
+            request: {
+                #[ruma_api(raw_body)]
+                pub file: Vec<u8>,
+                pub filename: String,
+            }
+            response: {}
+        };
+
+        assert!(syn::parse2::<Api>(tokens).is_err());
+    }
+
+    #[test]
+    fn raw_body_allowed_alongside_other_fields_in_multipart() {
+        let metadata = base_metadata();
+        let tokens = quote! {
+            #metadata
+            #[ruma_api(body_format = "multipart")]
+            request: {
+                #[ruma_api(raw_body)]
+                pub file: Vec<u8>,
+                pub filename: String,
+            }
+            response: {}
+        };
+
+        assert!(syn::parse2::<Api>(tokens).is_ok());
+    }
+
+    #[test]
+    fn newtype_body_rejects_non_json_format() {
+        let metadata = base_metadata();
+        let tokens = quote! {
+            #metadata
+            request: {}
+            #[ruma_api(body_format = "form_urlencoded")]
+            response: {
+                #[ruma_api(body)]
+                pub result: String,
+            }
+        };
+
+        assert!(syn::parse2::<Api>(tokens).is_err());
+    }
+
+    #[test]
+    fn query_only_access_token_rejects_query_map() {
+        let tokens = quote! {
+            metadata: {
+                description: "test",
+                method: GET,
+                name: "test_endpoint",
+                path: "/test",
+                rate_limited: false,
+                authentication: QueryOnlyAccessToken,
+            }
+            request: {
+                #[ruma_api(query_map)]
+                pub params: ::std::collections::BTreeMap<String, String>,
+            }
+            response: {}
+        };
+
+        assert!(syn::parse2::<Api>(tokens).is_err());
+    }
+
+    #[test]
+    fn timeout_is_accepted_in_metadata() {
+        let tokens = quote! {
+            metadata: {
+                description: "test",
+                method: GET,
+                name: "test_endpoint",
+                path: "/test",
+                rate_limited: false,
+                authentication: None,
+                timeout: 30,
+            }
+            request: {}
+            response: {}
+        };
+
+        assert!(syn::parse2::<Api>(tokens).is_ok());
+    }
+}
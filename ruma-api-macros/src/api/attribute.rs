@@ -0,0 +1,119 @@
+//! Parsing and representation of `#[ruma_api(...)]` field attributes.
+
+use syn::{
+    parse::{Parse, ParseStream},
+    Field, Path, Token,
+};
+
+mod kw {
+    syn::custom_keyword!(body);
+    syn::custom_keyword!(raw_body);
+    syn::custom_keyword!(path);
+    syn::custom_keyword!(query);
+    syn::custom_keyword!(query_map);
+    syn::custom_keyword!(header);
+    syn::custom_keyword!(body_format);
+}
+
+/// A single field-level attribute understood inside `#[ruma_api(...)]`.
+pub enum RumaApiAttr {
+    /// `#[ruma_api(body)]` – this field is the entire (typed, JSON) request/response body.
+    NewtypeBody,
+    /// `#[ruma_api(raw_body)]` – this field carries the verbatim, unparsed body bytes.
+    RawBody,
+    /// `#[ruma_api(path)]` – this field is interpolated into the request path.
+    Path,
+    /// `#[ruma_api(query)]` – this field is part of the query string.
+    Query,
+    /// `#[ruma_api(query_map)]` – this field captures the entire query string.
+    QueryMap,
+    /// `#[ruma_api(header = HEADER_NAME)]` – this field is sent/received as a single header.
+    Header(Path),
+}
+
+impl Parse for RumaApiAttr {
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        let lookahead = input.lookahead1();
+        if lookahead.peek(kw::header) {
+            let _: kw::header = input.parse()?;
+            let _: Token![=] = input.parse()?;
+            Ok(Self::Header(input.parse()?))
+        } else if lookahead.peek(kw::raw_body) {
+            let _: kw::raw_body = input.parse()?;
+            Ok(Self::RawBody)
+        } else if lookahead.peek(kw::body) {
+            let _: kw::body = input.parse()?;
+            Ok(Self::NewtypeBody)
+        } else if lookahead.peek(kw::path) {
+            let _: kw::path = input.parse()?;
+            Ok(Self::Path)
+        } else if lookahead.peek(kw::query_map) {
+            let _: kw::query_map = input.parse()?;
+            Ok(Self::QueryMap)
+        } else if lookahead.peek(kw::query) {
+            let _: kw::query = input.parse()?;
+            Ok(Self::Query)
+        } else {
+            Err(lookahead.error())
+        }
+    }
+}
+
+/// The content-body encoding used for a whole `request` or `response` section.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BodyFormat {
+    /// `application/json` (the default).
+    Json,
+    /// `application/x-www-form-urlencoded`, with the collected body fields as form fields.
+    FormUrlEncoded,
+    /// `multipart/form-data`, with the collected body fields as parts.
+    Multipart,
+}
+
+impl Default for BodyFormat {
+    fn default() -> Self {
+        Self::Json
+    }
+}
+
+/// Parses the optional `#[ruma_api(body_format = "...")]` attribute that may precede a `request`
+/// or `response` section, defaulting to `BodyFormat::Json` if it is absent.
+pub fn parse_body_format(attrs: &[syn::Attribute]) -> syn::Result<BodyFormat> {
+    for attr in attrs {
+        if attr.path.is_ident("ruma_api") {
+            let value: syn::LitStr = attr.parse_args_with(|input: ParseStream<'_>| {
+                input.parse::<kw::body_format>()?;
+                input.parse::<Token![=]>()?;
+                input.parse()
+            })?;
+
+            return match value.value().as_str() {
+                "form_urlencoded" => Ok(BodyFormat::FormUrlEncoded),
+                "multipart" => Ok(BodyFormat::Multipart),
+                other => Err(syn::Error::new_spanned(
+                    value,
+                    format!(
+                        "unknown `body_format` `{}`, expected `form_urlencoded` or `multipart`",
+                        other
+                    ),
+                )),
+            };
+        }
+    }
+
+    Ok(BodyFormat::default())
+}
+
+/// Returns the parsed `#[ruma_api(...)]` attribute on `field`, if any.
+///
+/// A field with no such attribute is treated as a plain body field, encoded according to the
+/// section's `BodyFormat`.
+pub fn get_ruma_api_attr(field: &Field) -> syn::Result<Option<RumaApiAttr>> {
+    for attr in &field.attrs {
+        if attr.path.is_ident("ruma_api") {
+            return attr.parse_args().map(Some);
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,152 @@
+//! Details of the `metadata` section of the procedural macro.
+
+use std::time::Duration;
+
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use syn::{
+    braced,
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+    Ident, LitBool, LitInt, LitStr, Token,
+};
+
+mod kw {
+    syn::custom_keyword!(metadata);
+    syn::custom_keyword!(description);
+    syn::custom_keyword!(method);
+    syn::custom_keyword!(name);
+    syn::custom_keyword!(path);
+    syn::custom_keyword!(rate_limited);
+    syn::custom_keyword!(authentication);
+    syn::custom_keyword!(timeout);
+}
+
+/// The `metadata` section of the `ruma_api!` macro.
+pub struct Metadata {
+    pub description: LitStr,
+    pub method: Ident,
+    pub name: LitStr,
+    pub path: LitStr,
+    pub rate_limited: LitBool,
+    pub authentication: Ident,
+    /// How long a client should wait for a response before giving up, in seconds.
+    ///
+    /// Optional: long-polling endpoints like `/sync` and quick ones like `/versions` have very
+    /// different sensible defaults, so there is no blanket value applied when this is omitted.
+    pub timeout: Option<Duration>,
+}
+
+impl Metadata {
+    /// The `timeout` field as an `Option<std::time::Duration>` expression, suitable for
+    /// splicing into the generated `ruma_api::Metadata` struct literal.
+    pub fn timeout_tokens(&self) -> TokenStream {
+        match self.timeout {
+            Some(duration) => {
+                let secs = duration.as_secs();
+                quote! { ::std::option::Option::Some(::std::time::Duration::from_secs(#secs)) }
+            }
+            None => quote! { ::std::option::Option::None },
+        }
+    }
+}
+
+/// A single `key: value` pair inside the `metadata { ... }` block.
+enum Field {
+    Description(LitStr),
+    Method(Ident),
+    Name(LitStr),
+    Path(LitStr),
+    RateLimited(LitBool),
+    Authentication(Ident),
+    Timeout(LitInt),
+}
+
+impl Parse for Field {
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        let lookahead = input.lookahead1();
+        if lookahead.peek(kw::description) {
+            input.parse::<kw::description>()?;
+            input.parse::<Token![:]>()?;
+            Ok(Self::Description(input.parse()?))
+        } else if lookahead.peek(kw::method) {
+            input.parse::<kw::method>()?;
+            input.parse::<Token![:]>()?;
+            Ok(Self::Method(input.parse()?))
+        } else if lookahead.peek(kw::name) {
+            input.parse::<kw::name>()?;
+            input.parse::<Token![:]>()?;
+            Ok(Self::Name(input.parse()?))
+        } else if lookahead.peek(kw::path) {
+            input.parse::<kw::path>()?;
+            input.parse::<Token![:]>()?;
+            Ok(Self::Path(input.parse()?))
+        } else if lookahead.peek(kw::rate_limited) {
+            input.parse::<kw::rate_limited>()?;
+            input.parse::<Token![:]>()?;
+            Ok(Self::RateLimited(input.parse()?))
+        } else if lookahead.peek(kw::authentication) {
+            input.parse::<kw::authentication>()?;
+            input.parse::<Token![:]>()?;
+            Ok(Self::Authentication(input.parse()?))
+        } else if lookahead.peek(kw::timeout) {
+            input.parse::<kw::timeout>()?;
+            input.parse::<Token![:]>()?;
+            Ok(Self::Timeout(input.parse()?))
+        } else {
+            Err(lookahead.error())
+        }
+    }
+}
+
+impl Parse for Metadata {
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        input.parse::<kw::metadata>()?;
+        input.parse::<Token![:]>()?;
+
+        let content;
+        braced!(content in input);
+        let fields: Punctuated<Field, Token![,]> = content.parse_terminated(Field::parse)?;
+
+        let mut description = None;
+        let mut method = None;
+        let mut name = None;
+        let mut path = None;
+        let mut rate_limited = None;
+        let mut authentication = None;
+        let mut timeout = None;
+
+        for field in fields {
+            match field {
+                Field::Description(v) => description = Some(v),
+                Field::Method(v) => method = Some(v),
+                Field::Name(v) => name = Some(v),
+                Field::Path(v) => path = Some(v),
+                Field::RateLimited(v) => rate_limited = Some(v),
+                Field::Authentication(v) => authentication = Some(v),
+                Field::Timeout(v) => timeout = Some(Duration::from_secs(v.base10_parse()?)),
+            }
+        }
+
+        macro_rules! require {
+            ($field:ident) => {
+                $field.ok_or_else(|| {
+                    syn::Error::new(
+                        Span::call_site(),
+                        concat!("missing required metadata field `", stringify!($field), "`"),
+                    )
+                })?
+            };
+        }
+
+        Ok(Self {
+            description: require!(description),
+            method: require!(method),
+            name: require!(name),
+            path: require!(path),
+            rate_limited: require!(rate_limited),
+            authentication: require!(authentication),
+            timeout,
+        })
+    }
+}
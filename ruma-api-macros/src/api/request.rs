@@ -0,0 +1,244 @@
+//! Details of the `request` section of the procedural macro.
+
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+use syn::{
+    braced,
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+    visit::Visit,
+    Field, Lifetime, Token,
+};
+
+use super::{
+    attribute::{get_ruma_api_attr, parse_body_format, BodyFormat, RumaApiAttr},
+    strip_serde_attrs,
+};
+
+mod kw {
+    syn::custom_keyword!(request);
+}
+
+/// The `request` section of the `ruma_api!` macro.
+pub struct Request {
+    body_format: BodyFormat,
+    fields: Punctuated<Field, Token![,]>,
+}
+
+impl Request {
+    fn attr(&self, field: &Field) -> Option<RumaApiAttr> {
+        get_ruma_api_attr(field).unwrap_or(None)
+    }
+
+    /// The body encoding selected via `#[ruma_api(body_format = "...")]`, or `Json` by default.
+    pub fn body_format(&self) -> BodyFormat {
+        self.body_format
+    }
+
+    /// Fields marked `#[ruma_api(path)]`.
+    pub fn path_fields(&self) -> impl Iterator<Item = &Field> {
+        self.fields.iter().filter(move |f| matches!(self.attr(f), Some(RumaApiAttr::Path)))
+    }
+
+    /// Whether this request interpolates any fields into its path.
+    pub fn has_path_fields(&self) -> bool {
+        self.path_fields().next().is_some()
+    }
+
+    /// Fields marked `#[ruma_api(query)]`.
+    pub fn query_fields(&self) -> impl Iterator<Item = &Field> {
+        self.fields.iter().filter(move |f| matches!(self.attr(f), Some(RumaApiAttr::Query)))
+    }
+
+    /// The single field marked `#[ruma_api(query_map)]`, if any.
+    pub fn query_map_field(&self) -> Option<&Field> {
+        self.fields.iter().find(|f| matches!(self.attr(f), Some(RumaApiAttr::QueryMap)))
+    }
+
+    /// Fields marked `#[ruma_api(header = ...)]`.
+    pub fn header_fields(&self) -> impl Iterator<Item = &Field> {
+        self.fields.iter().filter(move |f| matches!(self.attr(f), Some(RumaApiAttr::Header(_))))
+    }
+
+    /// Whether this request sends any fields as headers.
+    pub fn has_header_fields(&self) -> bool {
+        self.header_fields().next().is_some()
+    }
+
+    /// The single field marked `#[ruma_api(body)]`, if any.
+    pub fn newtype_body_field(&self) -> Option<&Field> {
+        self.fields.iter().find(|f| matches!(self.attr(f), Some(RumaApiAttr::NewtypeBody)))
+    }
+
+    /// The single field marked `#[ruma_api(raw_body)]`, if any.
+    ///
+    /// A raw body field carries the verbatim request/response bytes, bypassing `serde_json`
+    /// entirely. It is mutually exclusive with `newtype_body_field`, and with `body_fields` except
+    /// in `BodyFormat::Multipart` (see `has_conflicting_body_fields`).
+    pub fn raw_body_field(&self) -> Option<&Field> {
+        self.fields.iter().find(|f| matches!(self.attr(f), Some(RumaApiAttr::RawBody)))
+    }
+
+    /// Fields with no `#[ruma_api(...)]` attribute, collected into the request body and encoded
+    /// according to `body_format`.
+    pub fn body_fields(&self) -> impl Iterator<Item = &Field> {
+        self.fields.iter().filter(move |f| self.attr(f).is_none())
+    }
+
+    /// Whether this request has any plain body fields.
+    pub fn has_body_fields(&self) -> bool {
+        self.body_fields().next().is_some()
+    }
+
+    /// Whether any field's type contains a lifetime.
+    pub fn contains_lifetimes(&self) -> bool {
+        self.fields.iter().any(|f| !field_lifetimes(f).is_empty())
+    }
+
+    /// All lifetimes used by this request's fields, combined into a single generic param list
+    /// (e.g. `<'a, 'b>`, or an empty `TokenStream` if there are none).
+    pub fn combine_lifetimes(&self) -> TokenStream {
+        let mut lifetimes: Vec<&Lifetime> = Vec::new();
+        for lifetime in self.fields.iter().flat_map(field_lifetimes) {
+            if !lifetimes.contains(&lifetime) {
+                lifetimes.push(lifetime);
+            }
+        }
+        if lifetimes.is_empty() {
+            TokenStream::new()
+        } else {
+            quote! { <#(#lifetimes),*> }
+        }
+    }
+
+    /// Whether any body field (plain, newtype, or raw) borrows data.
+    pub fn has_body_lifetimes(&self) -> bool {
+        self.body_fields()
+            .chain(self.newtype_body_field())
+            .chain(self.raw_body_field())
+            .any(|f| !field_lifetimes(f).is_empty())
+    }
+
+    /// The number of distinct lifetimes used across this request's body fields.
+    pub fn body_lifetime_count(&self) -> usize {
+        let mut lifetimes: Vec<&Lifetime> = Vec::new();
+        let body_fields =
+            self.body_fields().chain(self.newtype_body_field()).chain(self.raw_body_field());
+        for lifetime in body_fields.flat_map(field_lifetimes) {
+            if !lifetimes.contains(&lifetime) {
+                lifetimes.push(lifetime);
+            }
+        }
+        lifetimes.len()
+    }
+
+    /// Whether a `raw_body_field` is present alongside a `newtype_body_field` or any plain body
+    /// fields, which is not allowed — a raw body claims the whole request/response body for
+    /// itself.
+    ///
+    /// The one exception is `BodyFormat::Multipart`, where a raw body field becomes a file part
+    /// that can sit alongside other fields encoded as text parts (e.g. a media upload with a
+    /// `filename` field next to the file bytes).
+    pub fn has_conflicting_body_fields(&self) -> bool {
+        if self.raw_body_field().is_none() {
+            return false;
+        }
+
+        self.newtype_body_field().is_some()
+            || (self.body_format != BodyFormat::Multipart && self.has_body_fields())
+    }
+
+    /// Struct-literal initializers for the non-map query fields, read out of the deserialized
+    /// `RequestQuery`.
+    pub fn request_init_query_fields(&self) -> TokenStream {
+        let fields = self.query_fields().map(|f| {
+            let ident = &f.ident;
+            quote! { #ident: request_query.#ident, }
+        });
+
+        quote! { #(#fields)* }
+    }
+
+    /// Code appending every header field onto `req_builder` when building an outgoing request.
+    pub fn append_header_kvs(&self) -> TokenStream {
+        let headers = self.header_fields().map(|f| {
+            let ident = f.ident.as_ref().expect("header fields have a name");
+            let header_name = match self.attr(f) {
+                Some(RumaApiAttr::Header(path)) => path,
+                _ => unreachable!("header_fields() only yields `#[ruma_api(header = ..)]` fields"),
+            };
+
+            quote! {
+                req_builder = req_builder.header(#header_name, #ident);
+            }
+        });
+
+        quote! { #(#headers)* }
+    }
+
+    /// Code reading every header field back out of an incoming request.
+    pub fn parse_headers_from_request(&self) -> TokenStream {
+        let fields = self.header_fields().map(|f| {
+            let ident = f.ident.as_ref().expect("header fields have a name");
+            let header_name = match self.attr(f) {
+                Some(RumaApiAttr::Header(path)) => path,
+                _ => unreachable!("header_fields() only yields `#[ruma_api(header = ..)]` fields"),
+            };
+
+            quote! {
+                #ident: headers.get(#header_name).and_then(|v| v.to_str().ok()).map(Into::into),
+            }
+        });
+
+        quote! { #(#fields)* }
+    }
+}
+
+impl Parse for Request {
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        let attrs = input.call(syn::Attribute::parse_outer)?;
+        let body_format = parse_body_format(&attrs)?;
+
+        input.parse::<kw::request>()?;
+        input.parse::<Token![:]>()?;
+
+        let content;
+        braced!(content in input);
+        let fields = content.parse_terminated(Field::parse_named)?;
+
+        Ok(Self { body_format, fields })
+    }
+}
+
+impl ToTokens for Request {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let fields = self.fields.iter().map(|f| {
+            let mut field = strip_serde_attrs(f);
+            field.attrs.retain(|attr| !attr.path.is_ident("ruma_api"));
+            field
+        });
+
+        tokens.extend(quote! {
+            #[derive(Debug, Clone)]
+            pub struct Request {
+                #(#fields),*
+            }
+        });
+    }
+}
+
+struct LifetimeCollector<'ast> {
+    lifetimes: Vec<&'ast Lifetime>,
+}
+
+impl<'ast> Visit<'ast> for LifetimeCollector<'ast> {
+    fn visit_lifetime(&mut self, lifetime: &'ast Lifetime) {
+        self.lifetimes.push(lifetime);
+    }
+}
+
+fn field_lifetimes(field: &Field) -> Vec<&Lifetime> {
+    let mut collector = LifetimeCollector { lifetimes: Vec::new() };
+    collector.visit_type(&field.ty);
+    collector.lifetimes
+}
@@ -0,0 +1,239 @@
+//! Details of the `response` section of the procedural macro.
+
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+use syn::{
+    braced,
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+    Field, Token,
+};
+
+use super::{
+    attribute::{get_ruma_api_attr, parse_body_format, BodyFormat, RumaApiAttr},
+    strip_serde_attrs,
+};
+use crate::util::multipart_text_parts;
+
+mod kw {
+    syn::custom_keyword!(response);
+}
+
+/// The `response` section of the `ruma_api!` macro.
+pub struct Response {
+    body_format: BodyFormat,
+    fields: Punctuated<Field, Token![,]>,
+}
+
+impl Response {
+    fn attr(&self, field: &Field) -> Option<RumaApiAttr> {
+        get_ruma_api_attr(field).unwrap_or(None)
+    }
+
+    /// The body encoding selected via `#[ruma_api(body_format = "...")]`, or `Json` by default.
+    pub fn body_format(&self) -> BodyFormat {
+        self.body_format
+    }
+
+    /// Fields marked `#[ruma_api(header = ...)]`.
+    pub fn header_fields(&self) -> impl Iterator<Item = &Field> {
+        self.fields.iter().filter(move |f| matches!(self.attr(f), Some(RumaApiAttr::Header(_))))
+    }
+
+    /// Whether this response sends any fields as headers.
+    pub fn has_header_fields(&self) -> bool {
+        self.header_fields().next().is_some()
+    }
+
+    /// The single field marked `#[ruma_api(body)]`, if any.
+    pub fn newtype_body_field(&self) -> Option<&Field> {
+        self.fields.iter().find(|f| matches!(self.attr(f), Some(RumaApiAttr::NewtypeBody)))
+    }
+
+    /// The single field marked `#[ruma_api(raw_body)]`, if any.
+    pub fn raw_body_field(&self) -> Option<&Field> {
+        self.fields.iter().find(|f| matches!(self.attr(f), Some(RumaApiAttr::RawBody)))
+    }
+
+    /// Fields with no `#[ruma_api(...)]` attribute, collected into the response body and encoded
+    /// according to `body_format`.
+    pub fn body_fields(&self) -> impl Iterator<Item = &Field> {
+        self.fields.iter().filter(move |f| self.attr(f).is_none())
+    }
+
+    /// Whether this response has any plain body fields.
+    pub fn has_body_fields(&self) -> bool {
+        self.body_fields().next().is_some()
+    }
+
+    /// Whether a `raw_body_field` is present alongside a `newtype_body_field` or any plain body
+    /// fields, which is not allowed — a raw body claims the whole response body for itself.
+    ///
+    /// The one exception is `BodyFormat::Multipart`, where a raw body field becomes a file part
+    /// that can sit alongside other fields encoded as text parts (e.g. a media upload with a
+    /// `filename` field next to the file bytes).
+    pub fn has_conflicting_body_fields(&self) -> bool {
+        if self.raw_body_field().is_none() {
+            return false;
+        }
+
+        self.newtype_body_field().is_some()
+            || (self.body_format != BodyFormat::Multipart && self.has_body_fields())
+    }
+
+    /// Struct-literal initializers for every field, read out of the parsed headers/body.
+    pub fn init_fields(&self) -> TokenStream {
+        let header_fields = self.header_fields().map(|f| {
+            let ident = &f.ident;
+            quote! { #ident, }
+        });
+
+        let body_fields = if let Some(field) = self.raw_body_field() {
+            let ident = &field.ident;
+
+            if self.body_format == BodyFormat::Multipart {
+                // Multipart mode: the raw field's bytes were extracted separately from the
+                // other parts (if any), which were deserialized into `ResponseBody` like a
+                // regular body. This matches `typed_response_body_decl`, which always
+                // destructures both out of the multipart envelope regardless of whether other
+                // body fields are present.
+                let other_fields = self.body_fields().map(|f| {
+                    let other_ident = &f.ident;
+                    quote! { #other_ident: response_body.#other_ident, }
+                });
+
+                quote! {
+                    #ident: response_raw_body.into(),
+                    #(#other_fields)*
+                }
+            } else {
+                quote! { #ident: response_body.into(), }
+            }
+        } else if let Some(field) = self.newtype_body_field() {
+            let ident = &field.ident;
+            quote! { #ident: response_body.0, }
+        } else {
+            self.body_fields()
+                .map(|f| {
+                    let ident = &f.ident;
+                    quote! { #ident: response_body.#ident, }
+                })
+                .collect()
+        };
+
+        quote! { #(#header_fields)* #body_fields }
+    }
+
+    /// The expression producing the outgoing response body passed to `resp_builder.body(..)`.
+    pub fn to_body(&self, ruma_api_import: &TokenStream) -> TokenStream {
+        if let Some(field) = self.raw_body_field() {
+            let ident = &field.ident;
+
+            if self.body_format == BodyFormat::Multipart {
+                let file_part_name =
+                    ident.as_ref().expect("body fields have a name").to_string();
+                let text_parts = multipart_text_parts(self.body_fields(), |ident| {
+                    quote! { response.#ident.to_string() }
+                });
+
+                return quote! {
+                    {
+                        let mut form = #ruma_api_import::exports::multipart::Form::default();
+                        form.add_file(#file_part_name, response.#ident.into());
+                        #text_parts
+                        form.into_bytes()
+                    }
+                };
+            }
+
+            return quote! { response.#ident.into() };
+        }
+
+        let body_value = if let Some(field) = self.newtype_body_field() {
+            let ident = &field.ident;
+            quote! { &response.#ident }
+        } else if self.has_body_fields() {
+            let inits = self.body_fields().map(|f| {
+                let ident = &f.ident;
+                quote! { #ident: response.#ident, }
+            });
+            quote! { &ResponseBody { #(#inits)* } }
+        } else {
+            return quote! { Vec::new() };
+        };
+
+        match self.body_format {
+            BodyFormat::FormUrlEncoded => quote! {
+                #ruma_api_import::exports::serde_html_form::to_string(#body_value)?.into_bytes()
+            },
+            BodyFormat::Multipart => {
+                let parts = multipart_text_parts(self.body_fields(), |ident| {
+                    quote! { response.#ident.to_string() }
+                });
+
+                quote! {
+                    {
+                        let mut form = #ruma_api_import::exports::multipart::Form::default();
+                        #parts
+                        form.into_bytes()
+                    }
+                }
+            }
+            BodyFormat::Json => quote! {
+                #ruma_api_import::exports::serde_json::to_vec(#body_value)?
+            },
+        }
+    }
+
+    /// Code writing every header field onto the outgoing `http::Response`.
+    pub fn apply_header_fields(&self) -> TokenStream {
+        let fields = self.header_fields().map(|f| {
+            let ident = f.ident.as_ref().expect("header fields have a name");
+            let header_name = match self.attr(f) {
+                Some(RumaApiAttr::Header(path)) => path,
+                _ => unreachable!("header_fields() only yields `#[ruma_api(header = ..)]` fields"),
+            };
+
+            quote! {
+                if let Ok(value) = ::std::convert::TryFrom::try_from(response.#ident) {
+                    headers.insert(#header_name, value);
+                }
+            }
+        });
+
+        quote! { #(#fields)* }
+    }
+}
+
+impl Parse for Response {
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        let attrs = input.call(syn::Attribute::parse_outer)?;
+        let body_format = parse_body_format(&attrs)?;
+
+        input.parse::<kw::response>()?;
+        input.parse::<Token![:]>()?;
+
+        let content;
+        braced!(content in input);
+        let fields = content.parse_terminated(Field::parse_named)?;
+
+        Ok(Self { body_format, fields })
+    }
+}
+
+impl ToTokens for Response {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let fields = self.fields.iter().map(|f| {
+            let mut field = strip_serde_attrs(f);
+            field.attrs.retain(|attr| !attr.path.is_ident("ruma_api"));
+            field
+        });
+
+        tokens.extend(quote! {
+            #[derive(Debug, Clone)]
+            pub struct Response {
+                #(#fields),*
+            }
+        });
+    }
+}
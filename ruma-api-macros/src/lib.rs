@@ -0,0 +1,18 @@
+//! Crate providing the `ruma_api!` procedural macro.
+
+#![recursion_limit = "256"]
+
+use proc_macro::TokenStream;
+use syn::parse_macro_input;
+
+mod api;
+mod util;
+
+/// Generates a `Request`/`Response` pair and the `http::Request`/`http::Response` conversions
+/// for a single Matrix API endpoint. See the `ruma_api` crate documentation for the full macro
+/// grammar.
+#[proc_macro]
+pub fn ruma_api(input: TokenStream) -> TokenStream {
+    let api = parse_macro_input!(input as api::Api);
+    api::expand_all(api).unwrap_or_else(|err| err.to_compile_error()).into()
+}
@@ -0,0 +1,259 @@
+//! Helpers shared between the different parts of `expand_all`.
+
+use proc_macro2::TokenStream;
+use proc_macro_crate::crate_name;
+use quote::quote;
+use syn::{Field, Ident};
+
+use crate::api::{attribute::BodyFormat, metadata::Metadata, request::Request};
+
+/// Builds the `form.add_text(name, ..)` calls for a multipart section's plain body fields.
+///
+/// `value_expr` turns a field's identifier into the expression reading its value off `self` (for
+/// an outgoing request) or `response` (for an outgoing response) — the two use sites differ only
+/// in which of those they read from.
+pub fn multipart_text_parts<'a>(
+    fields: impl Iterator<Item = &'a Field>,
+    value_expr: impl Fn(&Ident) -> TokenStream,
+) -> TokenStream {
+    let parts = fields.map(|f| {
+        let ident = f.ident.as_ref().expect("multipart fields have a name");
+        let name = ident.to_string();
+        let value = value_expr(ident);
+        quote! { form.add_text(#name, #value); }
+    });
+
+    quote! { #(#parts)* }
+}
+
+/// Returns a path to the `ruma_api` crate, accounting for the fact that it may have been renamed
+/// in the consuming crate's `Cargo.toml`, or that we may be inside `ruma_api` itself.
+pub fn import_ruma_api() -> TokenStream {
+    if let Ok(name) = crate_name("ruma-api") {
+        let import = Ident::new(&name, proc_macro2::Span::call_site());
+        quote! { ::#import }
+    } else {
+        quote! { ::ruma_api }
+    }
+}
+
+/// Builds the string literal/format-args used to interpolate path fields into the endpoint's
+/// path, and the code that parses them back out of an incoming request's path segments.
+pub fn request_path_string_and_parse(
+    request: &Request,
+    metadata: &Metadata,
+    ruma_api_import: &TokenStream,
+) -> (TokenStream, TokenStream) {
+    let path = &metadata.path;
+
+    if !request.has_path_fields() {
+        return (quote! { #path }, TokenStream::new());
+    }
+
+    let path_segments: Vec<&str> = path.value().split('/').skip(1).collect();
+    let format_string: String = path_segments
+        .iter()
+        .map(|segment| {
+            if segment.starts_with(':') {
+                "/{}".to_owned()
+            } else {
+                format!("/{}", segment)
+            }
+        })
+        .collect();
+
+    let path_field_names = request.path_fields().map(|f| f.ident.as_ref());
+    let path_string = quote! {
+        ::std::format!(#format_string, #(#ruma_api_import::exports::percent_encoding::utf8_percent_encode(
+            &self.#path_field_names.to_string(),
+            #ruma_api_import::exports::percent_encoding::NON_ALPHANUMERIC,
+        ),)*)
+    };
+
+    let parse_fields = path_segments
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| s.starts_with(':'))
+        .map(|(idx, _)| quote! { path_segments[#idx].parse()? });
+    let path_field_idents = request.path_fields().map(|f| &f.ident);
+
+    let parse_request_path = quote! {
+        #(#path_field_idents: #parse_fields,)*
+    };
+
+    (path_string, parse_request_path)
+}
+
+/// Builds the query-string portion appended to the outgoing request's URI.
+///
+/// When `query_only_access_token` is set (the `QueryOnlyAccessToken` authentication scheme), an
+/// `access_token=...` pair is appended as well, pulled out of the `access_token` parameter that
+/// `try_into_http_request` already takes, and `IntoHttpError::NeedsAuthentication` is raised if
+/// it's absent. `Api::parse` rejects this authentication scheme on endpoints with a
+/// `#[ruma_api(query_map)]` field, since a caller-controlled map could otherwise smuggle in its
+/// own `access_token` entry.
+pub fn build_query_string(
+    request: &Request,
+    ruma_api_import: &TokenStream,
+    query_only_access_token: bool,
+) -> TokenStream {
+    let base = if let Some(field) = request.query_map_field() {
+        let ident = &field.ident;
+        Some(quote! { #ruma_api_import::exports::serde_html_form::to_string(&self.#ident)? })
+    } else if request.query_fields().next().is_some() {
+        let fields = request.query_fields().map(|f| {
+            let ident = &f.ident;
+            quote! { #ident: &self.#ident, }
+        });
+
+        Some(quote! {
+            #ruma_api_import::exports::serde_html_form::to_string(
+                RequestQuery { #(#fields)* }
+            )?
+        })
+    } else {
+        None
+    };
+
+    if !query_only_access_token {
+        return match base {
+            Some(base) => quote! { format_args!("?{}", #base) },
+            None => quote! { "" },
+        };
+    }
+
+    let access_token_value = quote! {
+        #ruma_api_import::exports::percent_encoding::utf8_percent_encode(
+            access_token.ok_or(#ruma_api_import::error::IntoHttpError::NeedsAuthentication)?,
+            #ruma_api_import::exports::percent_encoding::NON_ALPHANUMERIC,
+        )
+    };
+
+    match base {
+        Some(base) => quote! { format_args!("?{}&access_token={}", #base, #access_token_value) },
+        None => quote! { format_args!("?access_token={}", #access_token_value) },
+    }
+}
+
+/// Code deserializing the incoming request's query string, if this request has any query fields.
+pub fn extract_request_query(request: &Request, ruma_api_import: &TokenStream) -> TokenStream {
+    if request.query_map_field().is_some() || request.query_fields().next().is_some() {
+        quote! {
+            let request_query = #ruma_api_import::exports::serde_html_form::from_str(
+                request.uri().query().unwrap_or(""),
+            )?;
+        }
+    } else {
+        TokenStream::new()
+    }
+}
+
+/// The expression producing the outgoing request body passed to `req_builder.body(..)`.
+pub fn build_request_body(request: &Request, ruma_api_import: &TokenStream) -> TokenStream {
+    if let Some(field) = request.raw_body_field() {
+        let ident = &field.ident;
+        return match request.body_format() {
+            BodyFormat::Multipart => {
+                let file_part_name = ident.as_ref().expect("body fields have a name").to_string();
+                let text_parts = multipart_text_parts(request.body_fields(), |ident| {
+                    quote! { self.#ident.to_string() }
+                });
+
+                quote! {
+                    {
+                        let mut form = #ruma_api_import::exports::multipart::Form::default();
+                        form.add_file(#file_part_name, self.#ident.into());
+                        #text_parts
+                        form.into_bytes()
+                    }
+                }
+            }
+            BodyFormat::Json | BodyFormat::FormUrlEncoded => quote! { self.#ident.into() },
+        };
+    }
+
+    if let Some(field) = request.newtype_body_field() {
+        let ident = &field.ident;
+        return quote! {
+            #ruma_api_import::exports::serde_json::to_vec(&self.#ident)?
+        };
+    }
+
+    if !request.has_body_fields() {
+        return quote! { Vec::new() };
+    }
+
+    match request.body_format() {
+        BodyFormat::Json => {
+            let fields = request.body_fields().map(|f| {
+                let ident = &f.ident;
+                quote! { #ident: &self.#ident, }
+            });
+
+            quote! {
+                #ruma_api_import::exports::serde_json::to_vec(&RequestBody { #(#fields)* })?
+            }
+        }
+        BodyFormat::FormUrlEncoded => {
+            let fields = request.body_fields().map(|f| {
+                let ident = &f.ident;
+                quote! { #ident: &self.#ident, }
+            });
+
+            quote! {
+                #ruma_api_import::exports::serde_html_form::to_string(
+                    &RequestBody { #(#fields)* }
+                )?.into_bytes()
+            }
+        }
+        BodyFormat::Multipart => {
+            let parts =
+                multipart_text_parts(request.body_fields(), |ident| quote! { self.#ident.to_string() });
+
+            quote! {
+                {
+                    let mut form = #ruma_api_import::exports::multipart::Form::default();
+                    #parts
+                    form.into_bytes()
+                }
+            }
+        }
+    }
+}
+
+/// Struct-literal initializers for the incoming request's fields, read out of the parsed body.
+pub fn parse_request_body(request: &Request) -> TokenStream {
+    if let Some(field) = request.raw_body_field() {
+        let ident = &field.ident;
+
+        if request.body_format() == BodyFormat::Multipart {
+            // Multipart mode: the raw field's bytes were extracted separately from the other
+            // parts (if any), which were deserialized into `RequestBody` like a regular body.
+            // This matches `extract_request_body`, which always destructures both out of the
+            // multipart envelope regardless of whether other body fields are present.
+            let other_fields = request.body_fields().map(|f| {
+                let other_ident = &f.ident;
+                quote! { #other_ident: request_body.#other_ident, }
+            });
+
+            return quote! {
+                #ident: request_raw_body.into(),
+                #(#other_fields)*
+            };
+        }
+
+        return quote! { #ident: request_body.into(), };
+    }
+
+    if let Some(field) = request.newtype_body_field() {
+        let ident = &field.ident;
+        return quote! { #ident: request_body.0, };
+    }
+
+    let fields = request.body_fields().map(|f| {
+        let ident = &f.ident;
+        quote! { #ident: request_body.#ident, }
+    });
+
+    quote! { #(#fields)* }
+}